@@ -0,0 +1,250 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, Context, Result};
+use chrono::NaiveTime;
+use clap::Parser;
+use notify::RecursiveMode;
+use regex::Regex;
+use serde::Deserialize;
+
+use crate::services::programs;
+
+/// Config-file form of [`programs::ProgramRule`]: a regex (rather than an
+/// already-compiled `Regex`) and time-of-day strings (rather than parsed
+/// `NaiveTime`s), since this is what actually gets deserialized from TOML.
+#[derive(Debug, Deserialize, Clone)]
+pub struct ProgramRule {
+    /// Matched against the filename without its extension. Must contain a
+    /// named capture group `timestamp`.
+    pub pattern: String,
+    /// chrono strptime format used to parse the `timestamp` capture group.
+    pub timestamp_format: String,
+    /// `"HH:MM:SS"` pair: inclusive start, exclusive end. Omit to match any
+    /// time of day.
+    pub time_of_day: Option<(String, String)>,
+    /// Title template; `{date}` is replaced with the recording's date.
+    pub title_template: String,
+    pub tag: String,
+    #[serde(default = "default_season_format")]
+    pub season_format: String,
+    #[serde(default = "default_episode_format")]
+    pub episode_format: String,
+}
+
+fn default_season_format() -> String {
+    "%Y".to_string()
+}
+
+fn default_episode_format() -> String {
+    "%m%d".to_string()
+}
+
+impl ProgramRule {
+    fn compile(&self) -> Result<programs::ProgramRule> {
+        let filename_pattern = Regex::new(&self.pattern)
+            .with_context(|| format!("invalid filename pattern '{}'", self.pattern))?;
+
+        // `classify` silently skips a rule if its pattern has no `timestamp`
+        // capture (see `programs::classify`), so a misconfigured rule here
+        // would just never match instead of failing fast at startup.
+        if !filename_pattern.capture_names().flatten().any(|name| name == "timestamp") {
+            return Err(anyhow!(
+                "filename pattern '{}' has no named capture group 'timestamp'",
+                self.pattern
+            ));
+        }
+
+        let time_of_day = match &self.time_of_day {
+            Some((start, end)) => Some((parse_time_of_day(start)?, parse_time_of_day(end)?)),
+            None => None,
+        };
+
+        Ok(programs::ProgramRule {
+            filename_pattern,
+            timestamp_format: self.timestamp_format.clone(),
+            time_of_day,
+            title_template: self.title_template.clone(),
+            tag: self.tag.clone(),
+            season_format: self.season_format.clone(),
+            episode_format: self.episode_format.clone(),
+        })
+    }
+}
+
+fn parse_time_of_day(value: &str) -> Result<NaiveTime> {
+    NaiveTime::parse_from_str(value, "%H:%M:%S")
+        .with_context(|| format!("invalid time-of-day '{}', expected HH:MM:SS", value))
+}
+
+/// Everything that used to be hard-coded in `main.rs`: watch roots, the
+/// output root, which extensions count as video, and how to watch them.
+/// Lets the same binary be deployed across multiple capture setups instead
+/// of being locked to a single hard-coded site.
+#[derive(Debug, Deserialize, Clone)]
+pub struct Config {
+    pub watch_paths: Vec<PathBuf>,
+    pub output_path: PathBuf,
+    #[serde(default = "default_video_extensions")]
+    pub video_extensions: Vec<String>,
+    #[serde(default)]
+    pub recursive: bool,
+    #[serde(default)]
+    pub programs: Vec<ProgramRule>,
+}
+
+fn default_video_extensions() -> Vec<String> {
+    ["mp4", "mkv", "mov", "webm"].into_iter().map(String::from).collect()
+}
+
+impl Default for Config {
+    /// The original RTSDA single-site layout, so a bare CLI invocation with
+    /// no config file still does something sensible.
+    fn default() -> Self {
+        Config {
+            watch_paths: vec![PathBuf::from("/home/rockvilleav/Sync/Livestreams")],
+            output_path: PathBuf::from("/media/archive/jellyfin/livestreams"),
+            video_extensions: default_video_extensions(),
+            recursive: false,
+            programs: Vec::new(),
+        }
+    }
+}
+
+impl Config {
+    /// Loads config from `path` if it exists, otherwise falls back to
+    /// [`Config::default`].
+    pub fn load(path: &Path) -> Result<Self> {
+        if !path.exists() {
+            return Ok(Config::default());
+        }
+
+        let contents = std::fs::read_to_string(path)
+            .with_context(|| format!("reading config file {}", path.display()))?;
+        toml::from_str(&contents).with_context(|| format!("parsing config file {}", path.display()))
+    }
+
+    pub fn is_recognized_extension(&self, ext: &str) -> bool {
+        self.video_extensions.iter().any(|known| known.eq_ignore_ascii_case(ext))
+    }
+
+    /// Compiles the config file's program rules, or falls back to
+    /// [`programs::default_rules`] (the original RTSDA schedule) if none
+    /// were configured.
+    pub fn program_rules(&self) -> Result<Vec<programs::ProgramRule>> {
+        if self.programs.is_empty() {
+            return Ok(programs::default_rules());
+        }
+
+        self.programs.iter().map(ProgramRule::compile).collect()
+    }
+
+    pub fn recursive_mode(&self) -> RecursiveMode {
+        if self.recursive {
+            RecursiveMode::Recursive
+        } else {
+            RecursiveMode::NonRecursive
+        }
+    }
+}
+
+/// CLI overrides layered on top of the config file. Anything left unset
+/// here falls back to whatever the config file (or its defaults) says.
+#[derive(Debug, Parser)]
+#[command(name = "livestream-archiver", about = "Watches for new livestream recordings and archives them as AV1 + HLS")]
+pub struct Cli {
+    /// Path to the TOML config file.
+    #[arg(short, long, default_value = "livestream-archiver.toml")]
+    pub config: PathBuf,
+
+    /// Additional directories to watch, on top of any in the config file.
+    #[arg(long = "watch")]
+    pub extra_watch_paths: Vec<PathBuf>,
+
+    /// Overrides the config file's output directory.
+    #[arg(long)]
+    pub output: Option<PathBuf>,
+
+    /// Watch directories recursively, overriding the config file's setting.
+    #[arg(long)]
+    pub recursive: bool,
+}
+
+impl Cli {
+    /// Loads the config file named on the command line, then layers CLI
+    /// overrides on top of it.
+    pub fn resolve(self) -> Result<Config> {
+        let mut config = Config::load(&self.config)?;
+
+        // `Config::load` falls back to `Config::default`'s hard-coded
+        // single-site RTSDA path whenever `self.config` doesn't exist. If
+        // the operator also passed `--watch` in that case, this is a fresh
+        // multi-site deployment with no config file at all, not one that
+        // additionally wants the original RTSDA path watched too.
+        if !self.config.exists() && !self.extra_watch_paths.is_empty() {
+            config.watch_paths.clear();
+        }
+
+        config.watch_paths.extend(self.extra_watch_paths);
+
+        if let Some(output) = self.output {
+            config.output_path = output;
+        }
+
+        if self.recursive {
+            config.recursive = true;
+        }
+
+        Ok(config)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rule(pattern: &str) -> ProgramRule {
+        ProgramRule {
+            pattern: pattern.to_string(),
+            timestamp_format: "%Y-%m-%d_%H-%M-%S".to_string(),
+            time_of_day: None,
+            title_template: "Program - {date}".to_string(),
+            tag: "Program".to_string(),
+            season_format: default_season_format(),
+            episode_format: default_episode_format(),
+        }
+    }
+
+    #[test]
+    fn compile_accepts_pattern_with_timestamp_group() {
+        let compiled = rule(r"^(?P<timestamp>\d{4}-\d{2}-\d{2}_\d{2}-\d{2}-\d{2})$").compile();
+        assert!(compiled.is_ok());
+    }
+
+    #[test]
+    fn compile_rejects_pattern_missing_timestamp_group() {
+        let err = rule(r"^\d{4}-\d{2}-\d{2}_\d{2}-\d{2}-\d{2}$")
+            .compile()
+            .expect_err("pattern has no 'timestamp' capture group");
+        assert!(err.to_string().contains("timestamp"));
+    }
+
+    fn cli(config: PathBuf, extra_watch_paths: Vec<PathBuf>) -> Cli {
+        Cli { config, extra_watch_paths, output: None, recursive: false }
+    }
+
+    #[test]
+    fn resolve_drops_default_watch_path_for_cli_only_multi_site_setup() {
+        let resolved = cli(PathBuf::from("/no/such/config.toml"), vec![PathBuf::from("/new/site/recordings")])
+            .resolve()
+            .expect("no config file is not an error");
+        assert_eq!(resolved.watch_paths, vec![PathBuf::from("/new/site/recordings")]);
+    }
+
+    #[test]
+    fn resolve_keeps_default_watch_path_when_no_watch_flags_given() {
+        let resolved = cli(PathBuf::from("/no/such/config.toml"), Vec::new())
+            .resolve()
+            .expect("no config file is not an error");
+        assert_eq!(resolved.watch_paths, Config::default().watch_paths);
+    }
+}
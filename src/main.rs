@@ -1,121 +1,277 @@
 use std::path::PathBuf;
 use anyhow::Result;
-use notify::{Watcher, RecursiveMode, Event, EventKind};
+use clap::Parser;
+use notify::{Watcher, Event, EventKind};
 use tokio::sync::mpsc;
-use std::collections::HashSet;
+use std::collections::HashMap;
+use std::net::SocketAddr;
 use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tracing::{debug, error, info, warn};
 
+mod config;
 mod services;
+use config::{Cli, Config};
 use services::livestream_archiver::LivestreamArchiver;
+use services::metrics;
+use services::processed_index::ProcessedIndex;
+
+/// Address the Prometheus text-format `/metrics` endpoint listens on.
+const METRICS_ADDR: &str = "127.0.0.1:9090";
+
+/// Default quiet period a path must go without a new event before it is
+/// forwarded for processing. OBS fsyncs produce bursts of `Modify` events
+/// while a recording is being written, and this coalesces them into one.
+const DEBOUNCE_MS: u64 = 200;
+
+/// How often the debounce map is swept for paths that have gone quiet.
+const DEBOUNCE_SWEEP_INTERVAL_MS: u64 = 50;
+
+/// Coalesces bursts of filesystem events into a single notification per
+/// path, once that path has been quiet for `debounce` duration.
+///
+/// This sits between the raw `notify` callback and `process_file`: it
+/// prevents the archiver from firing on a half-written recording, while
+/// `wait_for_file_ready` still handles the much longer multi-hour write
+/// session that follows.
+struct Debouncer {
+    pending: Arc<Mutex<HashMap<PathBuf, Instant>>>,
+}
+
+impl Debouncer {
+    /// Spawns the sweep task and returns a handle plus the channel that
+    /// yields each path once it has been quiet for `debounce`.
+    fn spawn(debounce: Duration) -> (Self, mpsc::Receiver<PathBuf>) {
+        let pending: Arc<Mutex<HashMap<PathBuf, Instant>>> = Arc::new(Mutex::new(HashMap::new()));
+        let (tx, rx) = mpsc::channel(100);
+
+        let sweep_pending = pending.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(Duration::from_millis(DEBOUNCE_SWEEP_INTERVAL_MS));
+            loop {
+                interval.tick().await;
+
+                let ready: Vec<PathBuf> = {
+                    let mut pending = sweep_pending.lock().unwrap();
+                    let now = Instant::now();
+                    let ready_paths: Vec<PathBuf> = pending
+                        .iter()
+                        .filter(|(_, last_seen)| now.duration_since(**last_seen) >= debounce)
+                        .map(|(path, _)| path.clone())
+                        .collect();
+
+                    for path in &ready_paths {
+                        pending.remove(path);
+                    }
+
+                    ready_paths
+                };
+
+                for path in ready {
+                    if tx.send(path).await.is_err() {
+                        // Receiver dropped; nothing left to do.
+                        return;
+                    }
+                }
+            }
+        });
+
+        (Debouncer { pending }, rx)
+    }
+
+    /// Records (or refreshes) activity for `path`, resetting its quiet
+    /// timer so a fresh burst of events doesn't get forwarded early.
+    fn notice(&self, path: PathBuf) {
+        self.pending.lock().unwrap().insert(path, Instant::now());
+    }
+}
+
+/// Processes `path` if (and only if) the persistent index doesn't already
+/// recognize it by path or by content fingerprint, then records the result.
+async fn process_if_new(path: PathBuf, archiver: &LivestreamArchiver, index: &ProcessedIndex) -> Result<()> {
+    let fingerprint = ProcessedIndex::fingerprint(&path).await?;
+
+    if index.is_processed(&path, &fingerprint)? {
+        let existing_output = index.lookup(&path)?.and_then(|entry| entry.output_path);
+        debug!(file = %path.display(), existing_output = ?existing_output, "skipping already processed file");
+        return Ok(());
+    }
+
+    info!(file = %path.display(), "processing file");
+    match archiver.process_file(path.clone()).await {
+        Ok(output_path) => {
+            // Recompute from the finished file rather than reusing the
+            // fingerprint above: `process_file` waits for the recording to
+            // stop growing before it returns, which can take hours, so the
+            // pre-wait fingerprint above is of a partial file and would
+            // never match a later rename/copy of the completed recording.
+            let metadata = tokio::fs::metadata(&path).await?;
+            let size = metadata.len();
+            let mtime_secs = metadata
+                .modified()?
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs() as i64;
+            let fingerprint = ProcessedIndex::fingerprint(&path).await?;
+            index.record(&path, size, mtime_secs, &fingerprint, &output_path)?;
+            Ok(())
+        }
+        Err(e) => Err(e),
+    }
+}
+
+/// Walks every watch root (recursively, if configured) and returns the
+/// canonicalized path of every file with a recognized video extension.
+/// Uses `dunce::canonicalize` rather than `std::fs::canonicalize` so paths
+/// stay as plain, non-UNC paths on Windows.
+fn discover_candidates(config: &Config) -> Vec<PathBuf> {
+    let mut candidates = Vec::new();
+
+    for root in &config.watch_paths {
+        let mut walker = walkdir::WalkDir::new(root);
+        if !config.recursive {
+            walker = walker.max_depth(1);
+        }
+
+        for entry in walker.into_iter().filter_map(|entry| entry.ok()) {
+            if !entry.file_type().is_file() {
+                continue;
+            }
+
+            let path = entry.path();
+            let is_recognized = path
+                .extension()
+                .and_then(|ext| ext.to_str())
+                .map(|ext| config.is_recognized_extension(ext))
+                .unwrap_or(false);
+            if !is_recognized {
+                continue;
+            }
+
+            match dunce::canonicalize(path) {
+                Ok(canonical) => candidates.push(canonical),
+                Err(e) => warn!(path = %path.display(), error = %e, "failed to canonicalize"),
+            }
+        }
+    }
+
+    candidates
+}
 
 #[tokio::main]
 async fn main() -> Result<()> {
-    let watch_path = PathBuf::from("/home/rockvilleav/Sync/Livestreams");
-    let output_path = PathBuf::from("/media/archive/jellyfin/livestreams");
+    tracing_subscriber::fmt::init();
+
+    let config = Cli::parse().resolve()?;
+
+    let metrics_addr: SocketAddr = METRICS_ADDR.parse()?;
+    metrics::install_prometheus_exporter(metrics_addr)?;
+    info!(addr = %metrics_addr, "serving Prometheus metrics");
 
-    // Ensure directories exist
-    if !watch_path.exists() {
-        std::fs::create_dir_all(&watch_path)?;
+    for watch_path in &config.watch_paths {
+        if !watch_path.exists() {
+            std::fs::create_dir_all(watch_path)?;
+        }
+    }
+    if !config.output_path.exists() {
+        std::fs::create_dir_all(&config.output_path)?;
     }
-    if !output_path.exists() {
-        std::fs::create_dir_all(&output_path)?;
+
+    info!("starting livestream archiver service");
+    for watch_path in &config.watch_paths {
+        info!(watch_path = %watch_path.display(), "watching directory");
     }
+    info!(output_path = %config.output_path.display(), "output directory");
 
-    println!("Starting livestream archiver service...");
-    println!("Watching directory: {}", watch_path.display());
-    println!("Output directory: {}", output_path.display());
+    let index_path = config.output_path.join("processed_files.sqlite3");
 
-    let archiver = LivestreamArchiver::new(&output_path);
-    let processed_files = Arc::new(Mutex::new(HashSet::new()));
+    let mut archiver = LivestreamArchiver::new(config.output_path.clone())
+        .with_recognized_extensions(config.video_extensions.clone())
+        .with_program_rules(config.program_rules()?);
+    archiver.probe_and_select_encoder().await?;
+    let archiver = Arc::new(archiver);
+
+    let index = Arc::new(ProcessedIndex::open(&index_path)?);
+
+    // Reconcile the index against what's actually on disk before doing
+    // anything else, so a source that was deleted or moved while the
+    // service was down doesn't linger in the database forever.
+    let pruned = index.reconcile()?;
+    if pruned > 0 {
+        info!(pruned, "pruned stale entries from the processed-file index");
+    }
 
     // Process existing files first
-    println!("Checking for existing files...");
-    if let Ok(entries) = std::fs::read_dir(&watch_path) {
-        for entry in entries {
-            if let Ok(entry) = entry {
-                let path = entry.path();
-                // Only process .mp4 files
-                if path.extension().and_then(|ext| ext.to_str()) == Some("mp4") {
-                    // Extract date from filename to check if output exists
-                    if let Some(filename) = path.file_name().and_then(|f| f.to_str()) {
-                        if let Ok(date) = archiver.extract_date_from_filename(filename).await {
-                            // Check if either Divine Worship or Afternoon Program exists for this date
-                            let year_dir = output_path.join(date.format("%Y").to_string());
-                            let month_dir = year_dir.join(format!("{}-{}", 
-                                date.format("%m"),
-                                date.format("%B")
-                            ));
-                            
-                            let divine_worship_file = month_dir.join(format!(
-                                "Divine Worship Service - RTSDA | {}.mp4",
-                                date.format("%B %d %Y")
-                            ));
-                            let afternoon_program_file = month_dir.join(format!(
-                                "Afternoon Program - RTSDA | {}.mp4",
-                                date.format("%B %d %Y")
-                            ));
-                            
-                            if !divine_worship_file.exists() && !afternoon_program_file.exists() {
-                                println!("Found unprocessed file: {}", path.display());
-                                if let Err(e) = archiver.process_file(path).await {
-                                    eprintln!("Error processing existing file: {}", e);
-                                }
-                            } else {
-                                println!("Skipping already processed file: {}", path.display());
-                            }
-                        }
-                    }
-                }
-            }
+    info!("scanning watch directories for existing files");
+    for path in discover_candidates(&config) {
+        if let Err(e) = process_if_new(path.clone(), &archiver, &index).await {
+            error!(file = %path.display(), error = %e, "error processing existing file");
         }
     }
 
-    // Set up file watcher for new files
+    // Set up file watchers for new files
     let (tx, mut rx) = mpsc::channel(100);
-    
+
+    let (debouncer, mut stable_paths) = Debouncer::spawn(Duration::from_millis(DEBOUNCE_MS));
+
     let mut watcher = notify::recommended_watcher(move |res: Result<Event, notify::Error>| {
         let tx = tx.clone();
         match res {
             Ok(event) => {
-                println!("Received event: {:?}", event);
+                debug!(?event, "received filesystem event");
                 if let Err(e) = tx.blocking_send(event) {
-                    eprintln!("Error sending event: {}", e);
+                    error!(error = %e, "error sending event");
                 }
             }
-            Err(e) => eprintln!("Watch error: {}", e),
+            Err(e) => error!(error = %e, "watch error"),
         }
     })?;
 
-    watcher.watch(&watch_path, RecursiveMode::NonRecursive)?;
-
-    while let Some(event) = rx.recv().await {
-        println!("Processing event: {:?}", event);
-        
-        match event.kind {
-            EventKind::Create(_) | EventKind::Modify(_) => {
-                for path in event.paths {
-                    if let Ok(canonical_path) = std::fs::canonicalize(&path) {
-                        let path_str = canonical_path.to_string_lossy().to_string();
-                        let mut processed = processed_files.lock().unwrap();
-                        
-                        if !processed.contains(&path_str) {
-                            println!("Processing file: {}", path_str);
-                            if let Err(e) = archiver.process_file(path).await {
-                                eprintln!("Error processing file: {}", e);
-                            } else {
-                                processed.insert(path_str);
-                                if processed.len() > 1000 {
-                                    processed.clear();
-                                }
-                            }
-                        } else {
-                            println!("Skipping already processed file: {}", path_str);
+    for watch_path in &config.watch_paths {
+        watcher.watch(watch_path, config.recursive_mode())?;
+    }
+
+    // Feed raw notify events into the debouncer; it only forwards a path
+    // to `stable_paths` once that path has gone quiet for `DEBOUNCE_MS`.
+    // Filtering by extension here also keeps our NFO files, HLS playlists
+    // and `.m4s` segments out of the debounce map if a watch root ever
+    // overlaps the output directory; the `.mp4` archives and each
+    // rendition's `init.mp4` still pass the filter.
+    let watch_config = config.clone();
+    tokio::spawn(async move {
+        while let Some(event) = rx.recv().await {
+            match event.kind {
+                EventKind::Create(_) | EventKind::Modify(_) => {
+                    for path in event.paths {
+                        let is_recognized = path
+                            .extension()
+                            .and_then(|ext| ext.to_str())
+                            .map(|ext| watch_config.is_recognized_extension(ext))
+                            .unwrap_or(false);
+                        if is_recognized {
+                            debouncer.notice(path);
                         }
                     }
                 }
-            },
-            _ => println!("Ignoring event: {:?}", event),
+                _ => debug!(?event, "ignoring event"),
+            }
         }
+    });
+
+    // Spawn a task per stabilized path rather than awaiting them in turn:
+    // `process_if_new` includes the multi-hour stability wait, and with
+    // multiple watch directories a second site's recording shouldn't have
+    // to wait for an unrelated first site's recording to finish its whole
+    // pipeline before it even starts watching for stability. The archiver
+    // itself still serializes the AV1 archive encode (not the HLS encodes).
+    while let Some(path) = stable_paths.recv().await {
+        let archiver = archiver.clone();
+        let index = index.clone();
+        tokio::spawn(async move {
+            if let Err(e) = process_if_new(path.clone(), &archiver, &index).await {
+                error!(file = %path.display(), error = %e, "error processing file");
+            }
+        });
     }
 
     Ok(())
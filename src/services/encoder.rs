@@ -0,0 +1,145 @@
+use anyhow::Result;
+use tokio::process::Command;
+
+/// An AV1 encode path ffmpeg can take, in the priority order we prefer
+/// them: hardware first, software as a fallback that works everywhere.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Av1Encoder {
+    /// Intel Quick Sync Video.
+    Qsv,
+    /// Nvidia NVENC.
+    Nvenc,
+    /// Software encode via SVT-AV1 (fast, good quality-per-cpu-second).
+    Svt,
+    /// Software encode via libaom-av1 (slower, used only if SVT is absent).
+    Aom,
+}
+
+impl Av1Encoder {
+    /// The ffmpeg `-c:v` value for this encoder.
+    pub fn codec_name(&self) -> &'static str {
+        match self {
+            Av1Encoder::Qsv => "av1_qsv",
+            Av1Encoder::Nvenc => "av1_nvenc",
+            Av1Encoder::Svt => "libsvtav1",
+            Av1Encoder::Aom => "libaom-av1",
+        }
+    }
+
+    /// Whether this encoder needs a hardware device initialized before the
+    /// input (`-init_hw_device`/`-hwaccel`).
+    pub fn is_hardware(&self) -> bool {
+        matches!(self, Av1Encoder::Qsv | Av1Encoder::Nvenc)
+    }
+
+    /// The next encoder to fall back to if this one's encode fails, if any.
+    /// Hardware encoders fall back to the best available software encoder;
+    /// software encoders have nowhere further to fall back to.
+    pub fn fallback(&self, available: &[Av1Encoder]) -> Option<Av1Encoder> {
+        if !self.is_hardware() {
+            return None;
+        }
+        [Av1Encoder::Svt, Av1Encoder::Aom]
+            .into_iter()
+            .find(|candidate| available.contains(candidate))
+    }
+
+    /// ffmpeg arguments that must come *before* `-i` for this encoder
+    /// (hardware device/acceleration setup). Empty for software encoders.
+    pub fn pre_input_args(&self) -> Vec<String> {
+        match self {
+            Av1Encoder::Qsv => vec![
+                "-init_hw_device".into(), "qsv=hw".into(),
+                "-filter_hw_device".into(), "hw".into(),
+                "-hwaccel".into(), "qsv".into(),
+                "-hwaccel_output_format".into(), "qsv".into(),
+            ],
+            Av1Encoder::Nvenc => vec![
+                "-hwaccel".into(), "cuda".into(),
+                "-hwaccel_output_format".into(), "cuda".into(),
+            ],
+            Av1Encoder::Svt | Av1Encoder::Aom => Vec::new(),
+        }
+    }
+
+    /// The `-preset`/`-cpu-used` argument pair for this encoder. Split out
+    /// from [`Self::encode_args`] so callers that supply their own bitrate
+    /// (e.g. the HLS bitrate ladder) can still get the right preset flag
+    /// for whichever encoder actually produced the source file.
+    pub fn preset_args(&self, profile: &EncodeProfile) -> Vec<String> {
+        match self {
+            Av1Encoder::Qsv | Av1Encoder::Nvenc => vec!["-preset".into(), profile.preset.clone()],
+            Av1Encoder::Svt => vec!["-preset".into(), profile.software_preset.clone()],
+            Av1Encoder::Aom => vec!["-cpu-used".into(), profile.software_preset.clone()],
+        }
+    }
+
+    /// The codec-specific video encode arguments that follow `-c:v <name>`:
+    /// the preset plus bitrate, maxrate and bufsize. Audio and output
+    /// options are not included.
+    pub fn encode_args(&self, profile: &EncodeProfile) -> Vec<String> {
+        let mut args = self.preset_args(profile);
+        args.extend([
+            "-b:v".into(), profile.bitrate.clone(),
+            "-maxrate".into(), profile.maxrate.clone(),
+            "-bufsize".into(), profile.bufsize.clone(),
+        ]);
+        args
+    }
+}
+
+/// Bitrate/preset settings threaded through to whichever encoder is chosen,
+/// so operators can tune quality per machine instead of editing literals.
+#[derive(Debug, Clone)]
+pub struct EncodeProfile {
+    pub bitrate: String,
+    pub maxrate: String,
+    pub bufsize: String,
+    /// `-preset` value for hardware encoders (e.g. QSV's numeric `4`).
+    pub preset: String,
+    /// `-preset`/`-cpu-used` value for software encoders, which use a very
+    /// different scale from hardware presets.
+    pub software_preset: String,
+}
+
+impl Default for EncodeProfile {
+    fn default() -> Self {
+        EncodeProfile {
+            bitrate: "6M".into(),
+            maxrate: "12M".into(),
+            bufsize: "24M".into(),
+            preset: "4".into(),
+            software_preset: "8".into(),
+        }
+    }
+}
+
+/// Runs `ffmpeg -encoders` and returns the AV1 encoders it advertises,
+/// ordered by preference (hardware first). Encoders ffmpeg doesn't list are
+/// left out rather than guessed at.
+pub async fn probe_available_encoders() -> Result<Vec<Av1Encoder>> {
+    let output = Command::new("ffmpeg").arg("-encoders").output().await?;
+    let listing = String::from_utf8_lossy(&output.stdout);
+
+    let candidates = [
+        ("av1_qsv", Av1Encoder::Qsv),
+        ("av1_nvenc", Av1Encoder::Nvenc),
+        ("libsvtav1", Av1Encoder::Svt),
+        ("libaom-av1", Av1Encoder::Aom),
+    ];
+
+    Ok(candidates
+        .into_iter()
+        .filter(|(name, _)| listing.lines().any(|line| line.contains(name)))
+        .map(|(_, encoder)| encoder)
+        .collect())
+}
+
+/// Picks the best AV1 encoder from what's available, in priority order:
+/// `av1_qsv`, then `av1_nvenc`, then software `libsvtav1`/`libaom-av1`.
+pub fn select_best(available: &[Av1Encoder]) -> Result<Av1Encoder> {
+    [Av1Encoder::Qsv, Av1Encoder::Nvenc, Av1Encoder::Svt, Av1Encoder::Aom]
+        .into_iter()
+        .find(|encoder| available.contains(encoder))
+        .ok_or_else(|| anyhow::anyhow!("ffmpeg has no usable AV1 encoder (checked QSV, NVENC, SVT-AV1, aom)"))
+}
@@ -0,0 +1,260 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, Result};
+use m3u8_rs::{
+    MasterPlaylist, MediaPlaylist, MediaPlaylistType, MediaSegment, Playlist, VariantStream,
+};
+use tokio::process::Command;
+use tracing::info;
+
+use super::encoder::{Av1Encoder, EncodeProfile};
+
+/// Target segment duration used when none is configured.
+pub const DEFAULT_SEGMENT_DURATION_SECS: u64 = 6;
+
+/// One rung of the bitrate ladder we transcode into HLS renditions.
+struct Rung {
+    name: &'static str,
+    resolution: &'static str,
+    bandwidth: u64,
+    video_bitrate: &'static str,
+    maxrate: &'static str,
+    bufsize: &'static str,
+    codecs: &'static str,
+}
+
+const LADDER: &[Rung] = &[
+    Rung {
+        name: "1080p",
+        resolution: "1920x1080",
+        bandwidth: 6_000_000,
+        video_bitrate: "6M",
+        maxrate: "6.5M",
+        bufsize: "12M",
+        codecs: "av01.0.09M.08,mp4a.40.2",
+    },
+    Rung {
+        name: "720p",
+        resolution: "1280x720",
+        bandwidth: 3_000_000,
+        video_bitrate: "3M",
+        maxrate: "3.3M",
+        bufsize: "6M",
+        codecs: "av01.0.05M.08,mp4a.40.2",
+    },
+    Rung {
+        name: "480p",
+        resolution: "854x480",
+        bandwidth: 1_000_000,
+        video_bitrate: "1M",
+        maxrate: "1.2M",
+        bufsize: "2M",
+        codecs: "av01.0.04M.08,mp4a.40.2",
+    },
+];
+
+/// Transcodes `source` into a VOD HLS package (master playlist + one media
+/// playlist and fMP4 segment set per rendition) under `package_dir`.
+///
+/// `package_dir` is created if missing and ends up laid out as:
+/// `<package_dir>/<rung>/init.mp4`, `<package_dir>/<rung>/segment_00001.m4s`,
+/// `<package_dir>/<rung>/<rung>.m3u8`, `<package_dir>/master.m3u8`.
+///
+/// The master playlist is only written once every variant has finished
+/// transcoding, so a half-built package never looks complete to a player.
+///
+/// `encoder` and `profile` should be whichever AV1 path actually produced
+/// `source` (the archiver's selected encoder, or the software fallback it
+/// fell back to), so a host without the hardware encoder doesn't fail here
+/// having already succeeded at the AV1 step.
+///
+/// If `package_dir` already contains a master playlist, this is a no-op, so
+/// a retry (or a service restart) doesn't redo a finished package. Without a
+/// master playlist every rendition is transcoded again, including any that
+/// finished on an earlier attempt.
+pub async fn generate_hls_package(
+    source: &Path,
+    package_dir: &Path,
+    segment_duration_secs: u64,
+    encoder: Av1Encoder,
+    profile: &EncodeProfile,
+) -> Result<PathBuf> {
+    let master_path = package_dir.join("master.m3u8");
+    if master_path.exists() {
+        info!(package = %package_dir.display(), "HLS package already exists; skipping");
+        return Ok(master_path);
+    }
+
+    tokio::fs::create_dir_all(package_dir).await?;
+
+    let mut variant_streams = Vec::with_capacity(LADDER.len());
+
+    for rung in LADDER {
+        info!(rendition = rung.name, source = %source.display(), "generating HLS rendition");
+        let media_playlist_uri =
+            transcode_rendition(source, package_dir, rung, segment_duration_secs, encoder, profile).await?;
+
+        variant_streams.push(VariantStream {
+            uri: media_playlist_uri,
+            bandwidth: rung.bandwidth,
+            resolution: Some(
+                rung.resolution
+                    .parse()
+                    .map_err(|_| anyhow!("invalid resolution literal {}", rung.resolution))?,
+            ),
+            codecs: Some(rung.codecs.to_string()),
+            ..Default::default()
+        });
+    }
+
+    // Only write the master playlist once every rendition above has
+    // succeeded, so a player never sees a partially-built package.
+    let master = MasterPlaylist {
+        version: Some(7),
+        variants: variant_streams,
+        independent_segments: true,
+        ..Default::default()
+    };
+
+    let mut bytes = Vec::new();
+    master
+        .write_to(&mut bytes)
+        .map_err(|e| anyhow!("failed to serialize master playlist: {}", e))?;
+    tokio::fs::write(&master_path, bytes).await?;
+
+    info!(master = %master_path.display(), "wrote HLS master playlist");
+
+    Ok(master_path)
+}
+
+/// Segments `source` into fMP4 chunks for a single rendition and writes that
+/// rendition's media playlist, returning the playlist's path relative to
+/// `package_dir` (the `uri` the master playlist should reference).
+async fn transcode_rendition(
+    source: &Path,
+    package_dir: &Path,
+    rung: &Rung,
+    segment_duration_secs: u64,
+    encoder: Av1Encoder,
+    profile: &EncodeProfile,
+) -> Result<String> {
+    let variant_dir = package_dir.join(rung.name);
+    tokio::fs::create_dir_all(&variant_dir).await?;
+
+    let init_segment = variant_dir.join("init.mp4");
+    let segment_pattern = variant_dir.join("segment_%05d.m4s");
+
+    let mut command = Command::new("ffmpeg");
+    // A retried rung may still have a prior attempt's segments/playlist on
+    // disk (this package's master playlist doesn't exist yet, or we'd have
+    // skipped the whole thing above, but an earlier rung may have already
+    // run); always overwrite rather than hitting ffmpeg's interactive
+    // prompt on our non-interactive stdin.
+    command.arg("-y");
+    for arg in encoder.pre_input_args() {
+        command.arg(arg);
+    }
+    command.arg("-i").arg(source);
+    command
+        .arg("-vf").arg(format!("scale={}", rung.resolution.replace('x', ":")))
+        .arg("-c:v").arg(encoder.codec_name());
+    for arg in encoder.preset_args(profile) {
+        command.arg(arg);
+    }
+
+    let status = command
+        .arg("-b:v").arg(rung.video_bitrate)
+        .arg("-maxrate").arg(rung.maxrate)
+        .arg("-bufsize").arg(rung.bufsize)
+        .arg("-c:a").arg("aac")
+        .arg("-b:a").arg("128k")
+        .arg("-f").arg("hls")
+        .arg("-hls_time").arg(segment_duration_secs.to_string())
+        .arg("-hls_playlist_type").arg("vod")
+        .arg("-hls_segment_type").arg("fmp4")
+        .arg("-hls_fmp4_init_filename").arg(&init_segment)
+        .arg("-hls_flags").arg("independent_segments")
+        .arg("-hls_segment_filename").arg(&segment_pattern)
+        // We only use this to drive ffmpeg's segmenter; the real playlist is
+        // rebuilt below with m3u8-rs so we control its contents exactly.
+        .arg(variant_dir.join("ffmpeg_generated.m3u8"))
+        .status()
+        .await?;
+
+    if !status.success() {
+        return Err(anyhow!("ffmpeg HLS segmenting failed for rendition {}", rung.name));
+    }
+
+    let segment_durations = read_segment_durations(&variant_dir).await?;
+    let media_playlist_name = format!("{}.m3u8", rung.name);
+    write_media_playlist(&variant_dir, &media_playlist_name, &segment_durations).await?;
+
+    Ok(format!("{}/{}", rung.name, media_playlist_name))
+}
+
+/// Reads the EXTINF durations ffmpeg produced so our own playlist reports
+/// accurate per-segment durations instead of a single repeated estimate.
+async fn read_segment_durations(variant_dir: &Path) -> Result<Vec<(String, f32)>> {
+    let ffmpeg_playlist_path = variant_dir.join("ffmpeg_generated.m3u8");
+    let bytes = tokio::fs::read(&ffmpeg_playlist_path).await?;
+
+    let parsed = m3u8_rs::parse_playlist_res(&bytes)
+        .map_err(|_| anyhow!("failed to parse ffmpeg-generated playlist {}", ffmpeg_playlist_path.display()))?;
+
+    let media_playlist = match parsed {
+        Playlist::MediaPlaylist(playlist) => playlist,
+        Playlist::MasterPlaylist(_) => {
+            return Err(anyhow!("expected a media playlist from ffmpeg, got a master playlist"))
+        }
+    };
+
+    Ok(media_playlist
+        .segments
+        .into_iter()
+        .map(|segment| (segment.uri, segment.duration))
+        .collect())
+}
+
+/// Builds and writes the rendition's media playlist ourselves, rather than
+/// reusing ffmpeg's own output file, so segment naming and the VOD end tag
+/// are consistent across every rendition.
+async fn write_media_playlist(
+    variant_dir: &Path,
+    playlist_name: &str,
+    segment_durations: &[(String, f32)],
+) -> Result<()> {
+    let segments = segment_durations
+        .iter()
+        .map(|(uri, duration)| MediaSegment {
+            uri: uri.clone(),
+            duration: *duration,
+            ..Default::default()
+        })
+        .collect::<Vec<_>>();
+
+    let target_duration = segments
+        .iter()
+        .map(|segment| segment.duration.ceil() as u64)
+        .max()
+        .unwrap_or(DEFAULT_SEGMENT_DURATION_SECS);
+
+    let playlist = MediaPlaylist {
+        version: Some(7),
+        target_duration,
+        media_sequence: 0,
+        segments,
+        playlist_type: Some(MediaPlaylistType::Vod),
+        end_list: true,
+        independent_segments: true,
+        ..Default::default()
+    };
+
+    let mut bytes = Vec::new();
+    playlist
+        .write_to(&mut bytes)
+        .map_err(|e| anyhow!("failed to serialize media playlist: {}", e))?;
+
+    tokio::fs::write(variant_dir.join(playlist_name), bytes).await?;
+
+    Ok(())
+}
@@ -1,198 +1,326 @@
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
 use anyhow::{Result, anyhow};
-use chrono::NaiveDateTime;
 use tokio::process::Command;
+use tokio::sync::Semaphore;
 use tokio::time::Duration;
+use tracing::{debug, info, instrument, warn};
+
+use super::encoder::{self, Av1Encoder, EncodeProfile};
+use super::hls;
+use super::metrics::ConversionGuard;
+use super::programs::{self, ProgramRule};
 
 pub struct LivestreamArchiver {
     output_path: PathBuf,
+    /// Target duration (in seconds) for each HLS segment. Defaults to
+    /// [`hls::DEFAULT_SEGMENT_DURATION_SECS`].
+    hls_segment_duration_secs: u64,
+    /// The AV1 encoder to use. Defaults to QSV until
+    /// [`Self::probe_and_select_encoder`] has run, since that's the only
+    /// path this archiver used before encoder probing existed.
+    encoder: Av1Encoder,
+    /// Every AV1 encoder ffmpeg advertised at probe time, used to pick a
+    /// software fallback if the selected hardware encoder fails.
+    available_encoders: Vec<Av1Encoder>,
+    encode_profile: EncodeProfile,
+    /// Source extensions (without the dot) this archiver will transcode.
+    recognized_extensions: Vec<String>,
+    /// Rules used to parse a recording's timestamp from its filename and
+    /// classify it into a program. Tried in order; see [`programs::classify`].
+    program_rules: Vec<ProgramRule>,
+    /// Serializes the actual ffmpeg AV1 encode across concurrently in-flight
+    /// recordings. Callers may run [`Self::process_file`] for several paths
+    /// at once so their (potentially hours-long) stability waits overlap,
+    /// but most hosts have only one usable hardware encoder, so the encode
+    /// itself is still taken one at a time. The HLS rendition encodes that
+    /// follow it do not take this gate.
+    encode_gate: Arc<Semaphore>,
 }
 
 impl LivestreamArchiver {
     pub fn new(output_path: PathBuf) -> Self {
         LivestreamArchiver {
             output_path,
+            hls_segment_duration_secs: hls::DEFAULT_SEGMENT_DURATION_SECS,
+            encoder: Av1Encoder::Qsv,
+            available_encoders: vec![Av1Encoder::Qsv],
+            encode_profile: EncodeProfile::default(),
+            recognized_extensions: vec!["mp4".to_string()],
+            program_rules: programs::default_rules(),
+            encode_gate: Arc::new(Semaphore::new(1)),
         }
     }
 
+    /// Overrides the default HLS segment target duration.
+    pub fn with_hls_segment_duration_secs(mut self, secs: u64) -> Self {
+        self.hls_segment_duration_secs = secs;
+        self
+    }
+
+    /// Overrides which source file extensions are recognized as video.
+    pub fn with_recognized_extensions(mut self, extensions: Vec<String>) -> Self {
+        self.recognized_extensions = extensions;
+        self
+    }
+
+    /// Overrides the default filename parsing/classification rules.
+    pub fn with_program_rules(mut self, rules: Vec<ProgramRule>) -> Self {
+        self.program_rules = rules;
+        self
+    }
+
+    /// Overrides the default bitrate/preset settings used for whichever
+    /// encoder ends up selected.
+    pub fn with_encode_profile(mut self, profile: EncodeProfile) -> Self {
+        self.encode_profile = profile;
+        self
+    }
+
+    /// Probes ffmpeg's available encoders and selects the best AV1 path, in
+    /// priority order: `av1_qsv`, then `av1_nvenc`, then software
+    /// `libsvtav1`/`libaom-av1`. Call this once at startup, before any
+    /// files are processed.
+    pub async fn probe_and_select_encoder(&mut self) -> Result<()> {
+        self.available_encoders = encoder::probe_available_encoders().await?;
+        self.encoder = encoder::select_best(&self.available_encoders)?;
+        info!(encoder = self.encoder.codec_name(), "selected AV1 encoder");
+        Ok(())
+    }
+
     pub fn get_output_path(&self) -> &PathBuf {
         &self.output_path
     }
    
     async fn wait_for_file_ready(&self, path: &PathBuf) -> Result<()> {
-        println!("Waiting for file to be ready: {}", path.display());
-        
+        info!("waiting for file to be ready");
+
         // Initial delay - let OBS get started
         tokio::time::sleep(Duration::from_secs(10)).await;
-        
+
         let mut last_size = 0;
         let mut stable_count = 0;
         let mut last_modified = std::time::SystemTime::now();
         let required_stable_checks = 15; // Must be stable for 30 seconds
-        
+
         // Check for up to 4 hours (14400 seconds / 2 second interval = 7200 iterations)
         for i in 0..7200 {
             match tokio::fs::metadata(path).await {
                 Ok(metadata) => {
                     let current_size = metadata.len();
                     let current_modified = metadata.modified()?;
-                    
-                    println!("Check {}: Size = {} bytes, Last Modified: {:?}", i, current_size, current_modified);
-                    
+
+                    debug!(check = i, size = current_size, modified = ?current_modified, "checked file stability");
+
                     if current_size > 0 {
                         if current_size == last_size {
                             // Also check if file hasn't been modified recently
                             if current_modified == last_modified {
                                 stable_count += 1;
-                                println!("Size and modification time stable for {} checks", stable_count);
-                                
+                                debug!(stable_count, "size and modification time stable");
+
                                 if stable_count >= required_stable_checks {
-                                    println!("File appears complete - size and modification time stable for 30 seconds");
+                                    info!("file stabilized");
                                     // Extra 30 second buffer after stability to be sure
                                     tokio::time::sleep(Duration::from_secs(30)).await;
                                     return Ok(());
                                 }
                             } else {
-                                println!("File still being modified");
+                                debug!("file still being modified");
                                 stable_count = 0;
                             }
                         } else {
-                            println!("Size changed: {} -> {}", last_size, current_size);
+                            debug!(from = last_size, to = current_size, "size changed");
                             stable_count = 0;
                         }
-                        
+
                         last_size = current_size;
                         last_modified = current_modified;
                     }
                 },
                 Err(e) => {
-                    println!("Error checking file: {}", e);
+                    warn!(error = %e, "failed to check file metadata");
                     return Err(anyhow!("Failed to check file metadata: {}", e));
                 }
             }
             tokio::time::sleep(Duration::from_secs(2)).await;
         }
-        
+
         // If we reach here, it timed out after 4 hours - something is wrong
-        println!("Timeout after 4 hours - file is still being written?");
+        warn!("timed out after 4 hours waiting for file to stabilize");
         Err(anyhow!("Timeout after 4 hours waiting for file to stabilize"))
     }
 
-    pub async fn extract_date_from_filename(&self, filename: &str) -> Result<NaiveDateTime> {
-        // Example filename: "2024-12-27_18-42-36.mp4"
-        let date_time_str = filename
-            .strip_suffix(".mp4")
-            .ok_or_else(|| anyhow!("Invalid filename format"))?;
-        
-        // Parse the full date and time
-        let date = NaiveDateTime::parse_from_str(date_time_str, "%Y-%m-%d_%H-%M-%S")?;
-        Ok(date)
+    /// Transcodes `path` to AV1 at `output_file` using the selected
+    /// encoder. If a hardware encode exits non-zero, automatically retries
+    /// once with the best available software encoder rather than failing
+    /// the whole recording outright. Returns whichever encoder actually
+    /// produced `output_file`, so later pipeline steps (HLS) can match it
+    /// instead of assuming the originally-selected one.
+    ///
+    /// If `output_file` already exists, this is a no-op: `run_ffmpeg_av1_encode`
+    /// refuses to overwrite existing output, so re-running this after the
+    /// AV1 step already succeeded (e.g. a retry following a failed HLS step)
+    /// would otherwise permanently fail instead of resuming. On that path the
+    /// selected encoder is returned, since which encoder produced an existing
+    /// file is not recorded.
+    async fn transcode_to_av1(&self, path: &PathBuf, output_file: &PathBuf) -> Result<Av1Encoder> {
+        if tokio::fs::try_exists(output_file).await? {
+            info!(output = %output_file.display(), "AV1 output already exists; skipping encode");
+            return Ok(self.encoder);
+        }
+
+        let _permit = self.encode_gate.acquire().await.expect("encode semaphore never closes");
+
+        if self.run_ffmpeg_av1_encode(self.encoder, path, output_file).await? {
+            return Ok(self.encoder);
+        }
+
+        let Some(fallback) = self.encoder.fallback(&self.available_encoders) else {
+            return Err(anyhow!("FFmpeg conversion failed using {}", self.encoder.codec_name()));
+        };
+
+        warn!(
+            failed_encoder = self.encoder.codec_name(),
+            fallback_encoder = fallback.codec_name(),
+            "hardware encode failed; falling back to software encoder"
+        );
+
+        if self.run_ffmpeg_av1_encode(fallback, path, output_file).await? {
+            return Ok(fallback);
+        }
+
+        Err(anyhow!("FFmpeg conversion failed using both {} and fallback {}", self.encoder.codec_name(), fallback.codec_name()))
+    }
+
+    /// Runs a single ffmpeg AV1 encode attempt with `encoder_choice`.
+    /// Returns `Ok(true)` on success, `Ok(false)` if ffmpeg exited non-zero.
+    async fn run_ffmpeg_av1_encode(&self, encoder_choice: Av1Encoder, path: &PathBuf, output_file: &PathBuf) -> Result<bool> {
+        let mut command = Command::new("ffmpeg");
+
+        for arg in encoder_choice.pre_input_args() {
+            command.arg(arg);
+        }
+
+        command.arg("-i").arg(path);
+        command.arg("-c:v").arg(encoder_choice.codec_name());
+
+        for arg in encoder_choice.encode_args(&self.encode_profile) {
+            command.arg(arg);
+        }
+
+        command
+            .arg("-c:a").arg("copy")
+            .arg("-n") // Never overwrite existing files
+            .arg(output_file);
+
+        let mut guard = ConversionGuard::start(encoder_choice.codec_name());
+        let status = command.status().await?;
+        let succeeded = status.success();
+        if succeeded {
+            guard.succeeded();
+        }
+        Ok(succeeded)
     }
 
-    pub async fn process_file(&self, path: PathBuf) -> Result<()> {
-        // Only process .mp4 files
-        if path.extension().and_then(|ext| ext.to_str()) != Some("mp4") {
-            return Err(anyhow!("Ignoring non-MP4 file"));
+    #[instrument(skip(self, path), fields(file = %path.display()))]
+    pub async fn process_file(&self, path: PathBuf) -> Result<PathBuf> {
+        // Only process recognized video source files.
+        let has_recognized_extension = path
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .map(|ext| self.recognized_extensions.iter().any(|known| known.eq_ignore_ascii_case(ext)))
+            .unwrap_or(false);
+        if !has_recognized_extension {
+            return Err(anyhow!("Ignoring file with unrecognized extension: {}", path.display()));
         }
 
-        println!("Processing livestream recording: {}", path.display());
+        info!("detected livestream recording");
 
         // Wait for file to be fully copied
         self.wait_for_file_ready(&path).await?;
-        
+
         // Get the filename
         let filename = path.file_name()
             .ok_or_else(|| anyhow!("Invalid filename"))?
             .to_str()
             .ok_or_else(|| anyhow!("Invalid UTF-8 in filename"))?;
+        let filename_stem = path
+            .file_stem()
+            .and_then(|stem| stem.to_str())
+            .ok_or_else(|| anyhow!("Invalid filename: {}", filename))?;
+
+        // Parse the recording's timestamp from its filename and classify it
+        // into a program, trying each configured rule in order.
+        let classification = programs::classify(filename_stem, &self.program_rules)?;
+        let date = classification.date;
+        let rule = classification.rule;
 
-        // Extract date from filename
-        let date = self.extract_date_from_filename(filename).await?;
-        
         // Create date-based directory structure
         let year_dir = self.output_path.join(date.format("%Y").to_string());
-        let month_dir = year_dir.join(format!("{}-{}", 
+        let month_dir = year_dir.join(format!("{}-{}",
             date.format("%m"),    // numeric month (12)
             date.format("%B")     // full month name (December)
         ));
-        
+
         // Create directories if they don't exist
         tokio::fs::create_dir_all(&month_dir).await?;
 
-        // Check for existing files
-        let divine_worship_file = month_dir.join(format!(
-            "Divine Worship Service - RTSDA | {}.mp4",
-            date.format("%B %d %Y")
-        ));
-        let afternoon_program_file = month_dir.join(format!(
-            "Afternoon Program - RTSDA | {}.mp4",
-            date.format("%B %d %Y")
-        ));
+        let title = rule.title_template.replace("{date}", &date.format("%B %d %Y").to_string());
+        let display_title = rule.title_template.replace("{date}", &date.format("%B %-d %Y").to_string());
 
-        // Determine which filename to use
-        let (base_filename, nfo_title, nfo_tag) = if !divine_worship_file.exists() {
-            (
-                format!("Divine Worship Service - RTSDA | {}", date.format("%B %d %Y")),
-                format!("Divine Worship Service - RTSDA | {}", date.format("%B %-d %Y")),
-                "Divine Worship Service"
-            )
-        } else if !afternoon_program_file.exists() {
-            (
-                format!("Afternoon Program - RTSDA | {}", date.format("%B %d %Y")),
-                format!("Afternoon Program - RTSDA | {}", date.format("%B %-d %Y")),
-                "Afternoon Program"
-            )
-        } else {
-            // Both exist, add suffix to Afternoon Program
-            let mut suffix = 1;
-            let mut test_file = month_dir.join(format!(
-                "Afternoon Program - RTSDA | {} ({}).mp4",
-                date.format("%B %d %Y"),
-                suffix
-            ));
-            while test_file.exists() {
-                suffix += 1;
-                test_file = month_dir.join(format!(
-                    "Afternoon Program - RTSDA | {} ({}).mp4",
-                    date.format("%B %d %Y"),
-                    suffix
-                ));
+        // If a recording with this title already exists for the day (e.g. a
+        // second recording matched the same rule), disambiguate with a
+        // " (N)" suffix rather than overwriting it. A candidate whose
+        // `.source` sidecar names this exact source path, though, isn't an
+        // unrelated recording to disambiguate against -- it's this same
+        // recording's own output from a run that was interrupted between
+        // the AV1 encode finishing and the NFO being written (the index
+        // only records a source once `process_file` returns, so a crash in
+        // that window leaves nothing to tell a restart this was already
+        // done). Reuse that exact filename so `transcode_to_av1` and
+        // `hls::generate_hls_package`'s existing-output skips can resume it
+        // instead of it becoming an orphan while we re-encode from scratch.
+        let mut base_filename = title.clone();
+        let mut nfo_title = display_title.clone();
+        let mut suffix = 1;
+        loop {
+            let candidate = month_dir.join(format!("{}.mp4", base_filename));
+            if !candidate.exists() || is_resume_candidate(&candidate, &path).await {
+                break;
             }
-            (
-                format!("Afternoon Program - RTSDA | {} ({})", date.format("%B %d %Y"), suffix),
-                format!("Afternoon Program - RTSDA | {} ({})", date.format("%B %-d %Y"), suffix),
-                "Afternoon Program"
-            )
-        };
+            suffix += 1;
+            base_filename = format!("{} ({})", title, suffix);
+            nfo_title = format!("{} ({})", display_title, suffix);
+        }
 
         let output_file = month_dir.join(format!("{}.mp4", base_filename));
-        
-        println!("Converting to AV1 and saving to: {}", output_file.display());
-
-        // Build ffmpeg command for AV1 conversion using QSV
-        let status = Command::new("ffmpeg")
-            .arg("-init_hw_device").arg("qsv=hw")
-            .arg("-filter_hw_device").arg("hw")
-            .arg("-hwaccel").arg("qsv")
-            .arg("-hwaccel_output_format").arg("qsv")
-            .arg("-i").arg(&path)
-            .arg("-c:v").arg("av1_qsv")
-            .arg("-preset").arg("4")
-            .arg("-b:v").arg("6M")
-            .arg("-maxrate").arg("12M")
-            .arg("-bufsize").arg("24M")
-            .arg("-c:a").arg("copy")
-            .arg("-n")  // Never overwrite existing files
-            .arg(&output_file)
-            .status()
-            .await?;
 
-        if !status.success() {
-            return Err(anyhow!("FFmpeg conversion failed"));
-        }
+        info!(output = %output_file.display(), "converting to AV1");
+
+        let av1_encoder = self.transcode_to_av1(&path, &output_file).await?;
+        tokio::fs::write(source_marker_path(&output_file), path.to_string_lossy().as_bytes()).await?;
+
+        info!("transcoded to AV1");
+
+        // Build an adaptive HLS package alongside the AV1 archive so the
+        // recording can be played directly in a browser/Jellyfin without a
+        // full download. Uses the encoder `transcode_to_av1` returned, not
+        // necessarily `self.encoder`, so a host that fell back to software
+        // during this run doesn't fail trying to re-invoke hardware here.
+        let hls_package_dir = month_dir.join(&base_filename);
+        hls::generate_hls_package(
+            &output_file,
+            &hls_package_dir,
+            self.hls_segment_duration_secs,
+            av1_encoder,
+            &self.encode_profile,
+        )
+        .await?;
+
+        info!("generated HLS web-streaming rendition");
 
         // Create NFO file
-        println!("Creating NFO file...");
         let nfo_content = format!(r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?>
 <episodedetails>
     <title>{}</title>
@@ -205,22 +333,46 @@ impl LivestreamArchiver {
     <tag>{}</tag>
 </episodedetails>"#,
             nfo_title,
-            date.format("%Y").to_string(),
-            date.format("%m%d").to_string(),
+            date.format(&rule.season_format).to_string(),
+            date.format(&rule.episode_format).to_string(),
             date.format("%Y-%m-%d"),
-            date.format("%Y"),
-            date.format("%m%d"),
-            nfo_tag
+            date.format(&rule.season_format).to_string(),
+            date.format(&rule.episode_format).to_string(),
+            rule.tag
         );
 
         let nfo_path = output_file.with_extension("nfo");
         tokio::fs::write(nfo_path, nfo_content).await?;
 
-        println!("Successfully converted {} to AV1 and created NFO", path.display());
+        info!("NFO written");
+
+        // Pipeline fully completed; drop the resume marker so a future,
+        // genuinely different recording never finds it and mistakes this
+        // finished output for its own in-progress one.
+        let _ = tokio::fs::remove_file(source_marker_path(&output_file)).await;
 
         // Don't delete original file
-        println!("Original file preserved at: {}", path.display());
+        debug!("original file preserved");
 
-        Ok(())
+        Ok(output_file)
+    }
+}
+
+/// Sidecar path recording which source file produced a given AV1 output, so
+/// an interrupted pipeline (AV1 done, HLS/NFO not yet) can be recognized on
+/// a later run as "this exact recording, still in progress" rather than an
+/// unrelated file to disambiguate a new filename against.
+fn source_marker_path(output_file: &Path) -> PathBuf {
+    output_file.with_extension("source")
+}
+
+/// True if `candidate`'s source marker names `source` -- i.e. `candidate`
+/// is this exact recording's own (possibly unfinished) output from an
+/// earlier, interrupted run, not a different recording that happened to get
+/// the same title.
+async fn is_resume_candidate(candidate: &Path, source: &Path) -> bool {
+    match tokio::fs::read_to_string(source_marker_path(candidate)).await {
+        Ok(recorded) => recorded == source.to_string_lossy(),
+        Err(_) => false,
     }
 }
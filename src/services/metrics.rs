@@ -0,0 +1,64 @@
+use std::net::SocketAddr;
+use std::time::Instant;
+
+use anyhow::{Context, Result};
+use metrics::{counter, histogram};
+use metrics_exporter_prometheus::PrometheusBuilder;
+
+/// Starts the Prometheus text-format exporter on `addr` (e.g.
+/// `127.0.0.1:9090`), serving whatever this process has recorded via the
+/// `metrics` crate at `/metrics`. Call this once at startup.
+pub fn install_prometheus_exporter(addr: SocketAddr) -> Result<()> {
+    PrometheusBuilder::new()
+        .with_http_listener(addr)
+        .install()
+        .context("failed to install Prometheus metrics exporter")
+}
+
+/// Tracks one ffmpeg conversion attempt: armed when created, and records a
+/// duration histogram plus a completed/failed counter when dropped. Call
+/// [`ConversionGuard::succeeded`] on the success path to disarm the
+/// default "failed" outcome before the guard is dropped.
+pub struct ConversionGuard {
+    start: Instant,
+    encoder: &'static str,
+    completed: bool,
+}
+
+impl ConversionGuard {
+    /// Arms a guard for a conversion attempt using `encoder`.
+    pub fn start(encoder: &'static str) -> Self {
+        ConversionGuard {
+            start: Instant::now(),
+            encoder,
+            completed: false,
+        }
+    }
+
+    /// Marks the attempt as successful. The metrics are actually emitted
+    /// when the guard drops, right after this.
+    pub fn succeeded(&mut self) {
+        self.completed = true;
+    }
+}
+
+impl Drop for ConversionGuard {
+    fn drop(&mut self) {
+        let elapsed_secs = self.start.elapsed().as_secs_f64();
+        let completed = self.completed.to_string();
+
+        histogram!(
+            "livestream_archiver_conversion_duration_seconds",
+            "encoder" => self.encoder,
+            "completed" => completed.clone(),
+        )
+        .record(elapsed_secs);
+
+        counter!(
+            "livestream_archiver_conversions_total",
+            "encoder" => self.encoder,
+            "completed" => completed,
+        )
+        .increment(1);
+    }
+}
@@ -0,0 +1,6 @@
+pub mod livestream_archiver;
+pub mod hls;
+pub mod processed_index;
+pub mod encoder;
+pub mod metrics;
+pub mod programs;
@@ -0,0 +1,188 @@
+use std::io::{Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::{anyhow, Result};
+use rusqlite::{params, Connection, OptionalExtension};
+use xxhash_rust::xxh3::xxh3_128;
+
+/// How many bytes to hash from the start and end of a file when computing
+/// its fingerprint. Files smaller than twice this are hashed in full.
+const FINGERPRINT_SAMPLE_BYTES: u64 = 4 * 1024 * 1024;
+
+/// A processed source recording, as recorded in the index.
+pub struct ProcessedEntry {
+    pub source_path: PathBuf,
+    pub size: u64,
+    pub mtime_secs: i64,
+    pub fingerprint: String,
+    pub output_path: Option<PathBuf>,
+}
+
+/// Persistent, content-addressed record of which source recordings have
+/// already been transcoded, backed by a small SQLite database.
+///
+/// Replaces the in-memory `HashSet<String>` of canonical paths: that set
+/// forgot everything on restart and reset itself every 1000 entries, so a
+/// restart (or a long-running service) could re-transcode files it had
+/// already processed. Keying on a content fingerprint (not just the path)
+/// also lets a renamed-but-identical source be recognized as already done.
+pub struct ProcessedIndex {
+    conn: Mutex<Connection>,
+}
+
+impl ProcessedIndex {
+    /// Opens (creating if necessary) the SQLite database at `db_path`.
+    pub fn open(db_path: &Path) -> Result<Self> {
+        let conn = Connection::open(db_path)?;
+        conn.execute_batch(
+            "CREATE TABLE IF NOT EXISTS processed_files (
+                id              INTEGER PRIMARY KEY,
+                source_path     TEXT NOT NULL UNIQUE,
+                size            INTEGER NOT NULL,
+                mtime_secs      INTEGER NOT NULL,
+                fingerprint     TEXT NOT NULL,
+                output_path     TEXT,
+                processed_at    INTEGER NOT NULL
+            );
+            CREATE INDEX IF NOT EXISTS idx_processed_files_fingerprint
+                ON processed_files(fingerprint);",
+        )?;
+
+        Ok(ProcessedIndex { conn: Mutex::new(conn) })
+    }
+
+    /// Computes the content fingerprint for `path`: an `xxh3_128` hash over
+    /// its first and last [`FINGERPRINT_SAMPLE_BYTES`] (or the whole file,
+    /// if it's no larger than twice that). Runs on a blocking thread since it does
+    /// synchronous file I/O.
+    pub async fn fingerprint(path: &Path) -> Result<String> {
+        let path = path.to_path_buf();
+        let hash = tokio::task::spawn_blocking(move || sample_for_fingerprint(&path)).await??;
+        Ok(format!("{:032x}", hash))
+    }
+
+    /// Returns `true` if a source with this path or fingerprint has already
+    /// been recorded, which covers both "already processed" and
+    /// "renamed but identical to something already processed".
+    pub fn is_processed(&self, source_path: &Path, fingerprint: &str) -> Result<bool> {
+        let conn = self.conn.lock().unwrap();
+        let found: Option<i64> = conn
+            .query_row(
+                "SELECT 1 FROM processed_files WHERE source_path = ?1 OR fingerprint = ?2",
+                params![source_path.to_string_lossy(), fingerprint],
+                |row| row.get(0),
+            )
+            .optional()?;
+        Ok(found.is_some())
+    }
+
+    /// Looks up the recorded entry for `source_path`, if any. Used to
+    /// report what a source was already archived as when skipping it.
+    pub fn lookup(&self, source_path: &Path) -> Result<Option<ProcessedEntry>> {
+        let conn = self.conn.lock().unwrap();
+        conn.query_row(
+            "SELECT source_path, size, mtime_secs, fingerprint, output_path
+             FROM processed_files WHERE source_path = ?1",
+            params![source_path.to_string_lossy()],
+            |row| {
+                let source_path: String = row.get(0)?;
+                let output_path: Option<String> = row.get(4)?;
+                Ok(ProcessedEntry {
+                    source_path: PathBuf::from(source_path),
+                    size: row.get::<_, i64>(1)? as u64,
+                    mtime_secs: row.get(2)?,
+                    fingerprint: row.get(3)?,
+                    output_path: output_path.map(PathBuf::from),
+                })
+            },
+        )
+        .optional()
+        .map_err(Into::into)
+    }
+
+    /// Records that `source_path` has been processed into `output_path`.
+    pub fn record(
+        &self,
+        source_path: &Path,
+        size: u64,
+        mtime_secs: i64,
+        fingerprint: &str,
+        output_path: &Path,
+    ) -> Result<()> {
+        let processed_at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_err(|e| anyhow!("system clock before epoch: {}", e))?
+            .as_secs() as i64;
+
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "INSERT INTO processed_files (source_path, size, mtime_secs, fingerprint, output_path, processed_at)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6)
+             ON CONFLICT(source_path) DO UPDATE SET
+                size = excluded.size,
+                mtime_secs = excluded.mtime_secs,
+                fingerprint = excluded.fingerprint,
+                output_path = excluded.output_path,
+                processed_at = excluded.processed_at",
+            params![
+                source_path.to_string_lossy(),
+                size as i64,
+                mtime_secs,
+                fingerprint,
+                output_path.to_string_lossy(),
+                processed_at,
+            ],
+        )?;
+        Ok(())
+    }
+
+    /// Removes index rows whose source recording no longer exists on disk,
+    /// so deleted or moved sources don't linger forever. Returns the number
+    /// of rows pruned.
+    pub fn reconcile(&self) -> Result<usize> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare("SELECT id, source_path FROM processed_files")?;
+        let stale_ids: Vec<i64> = stmt
+            .query_map([], |row| {
+                let id: i64 = row.get(0)?;
+                let source_path: String = row.get(1)?;
+                Ok((id, source_path))
+            })?
+            .filter_map(|row| row.ok())
+            .filter(|(_, source_path)| !Path::new(source_path).exists())
+            .map(|(id, _)| id)
+            .collect();
+
+        for id in &stale_ids {
+            conn.execute("DELETE FROM processed_files WHERE id = ?1", params![id])?;
+        }
+
+        Ok(stale_ids.len())
+    }
+}
+
+fn sample_for_fingerprint(path: &Path) -> Result<u128> {
+    let mut file = std::fs::File::open(path)?;
+    let len = file.metadata()?.len();
+
+    if len <= FINGERPRINT_SAMPLE_BYTES * 2 {
+        let mut buf = Vec::with_capacity(len as usize);
+        file.read_to_end(&mut buf)?;
+        return Ok(xxh3_128(&buf));
+    }
+
+    let mut buf = Vec::with_capacity((FINGERPRINT_SAMPLE_BYTES * 2) as usize);
+
+    let mut head = vec![0u8; FINGERPRINT_SAMPLE_BYTES as usize];
+    file.read_exact(&mut head)?;
+    buf.extend_from_slice(&head);
+
+    file.seek(SeekFrom::End(-(FINGERPRINT_SAMPLE_BYTES as i64)))?;
+    let mut tail = vec![0u8; FINGERPRINT_SAMPLE_BYTES as usize];
+    file.read_exact(&mut tail)?;
+    buf.extend_from_slice(&tail);
+
+    Ok(xxh3_128(&buf))
+}
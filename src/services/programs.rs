@@ -0,0 +1,150 @@
+use anyhow::{anyhow, Result};
+use chrono::{NaiveDateTime, NaiveTime};
+use regex::Regex;
+
+/// A site-specific rule for recognizing a recording's timestamp from its
+/// filename and, optionally, classifying it into a named program by the
+/// recording's start time-of-day.
+///
+/// Rules are tried in order; the first whose `filename_pattern` matches
+/// and whose `time_of_day` window (if any) contains the parsed time wins.
+/// This replaces the old hard-coded "exactly `%Y-%m-%d_%H-%M-%S.mp4`, and
+/// always Divine Worship followed by Afternoon Program" assumption with
+/// something a different site's schedule can describe for itself.
+pub struct ProgramRule {
+    /// Matched against the filename without its extension. Must contain a
+    /// named capture group `timestamp`.
+    pub filename_pattern: Regex,
+    /// chrono strptime format used to parse the `timestamp` capture group.
+    pub timestamp_format: String,
+    /// Restricts this rule to recordings starting within this window
+    /// (inclusive start, exclusive end). `None` matches any time of day.
+    pub time_of_day: Option<(NaiveTime, NaiveTime)>,
+    /// Title template; `{date}` is replaced with the recording's date.
+    pub title_template: String,
+    pub tag: String,
+    /// chrono strftime format used to derive the NFO `<season>`.
+    pub season_format: String,
+    /// chrono strftime format used to derive the NFO `<episode>`.
+    pub episode_format: String,
+}
+
+/// The result of classifying a recording: its parsed start time plus the
+/// rule that matched it.
+pub struct Classification<'a> {
+    pub date: NaiveDateTime,
+    pub rule: &'a ProgramRule,
+}
+
+/// Tries every rule in order against `filename_stem` (the filename with
+/// its extension already stripped), returning the first one whose pattern
+/// matches and whose time-of-day window (if any) contains the parsed
+/// timestamp.
+pub fn classify<'a>(filename_stem: &str, rules: &'a [ProgramRule]) -> Result<Classification<'a>> {
+    for rule in rules {
+        let Some(captures) = rule.filename_pattern.captures(filename_stem) else {
+            continue;
+        };
+        let Some(timestamp) = captures.name("timestamp") else {
+            continue;
+        };
+        let Ok(date) = NaiveDateTime::parse_from_str(timestamp.as_str(), &rule.timestamp_format) else {
+            continue;
+        };
+
+        if let Some((start, end)) = rule.time_of_day {
+            if !time_in_window(date.time(), start, end) {
+                continue;
+            }
+        }
+
+        return Ok(Classification { date, rule });
+    }
+
+    Err(anyhow!(
+        "no program rule matched filename '{}' ({} rule(s) configured)",
+        filename_stem,
+        rules.len()
+    ))
+}
+
+fn time_in_window(time: NaiveTime, start: NaiveTime, end: NaiveTime) -> bool {
+    if start <= end {
+        time >= start && time < end
+    } else {
+        // Window wraps past midnight.
+        time >= start || time < end
+    }
+}
+
+/// The rule set this archiver shipped with before rules became
+/// configurable: a morning "Divine Worship Service" and an afternoon
+/// "Afternoon Program", both parsed from the `%Y-%m-%d_%H-%M-%S` filenames
+/// OBS produces, disambiguated by the recording's start time rather than
+/// by which output file happens to exist yet.
+pub fn default_rules() -> Vec<ProgramRule> {
+    let timestamp_pattern = r"^(?P<timestamp>\d{4}-\d{2}-\d{2}_\d{2}-\d{2}-\d{2})$";
+    let one_pm = NaiveTime::from_hms_opt(13, 0, 0).expect("valid time");
+    let midnight = NaiveTime::from_hms_opt(0, 0, 0).expect("valid time");
+
+    vec![
+        ProgramRule {
+            filename_pattern: Regex::new(timestamp_pattern).expect("valid regex"),
+            timestamp_format: "%Y-%m-%d_%H-%M-%S".to_string(),
+            time_of_day: Some((midnight, one_pm)),
+            title_template: "Divine Worship Service - RTSDA | {date}".to_string(),
+            tag: "Divine Worship Service".to_string(),
+            season_format: "%Y".to_string(),
+            episode_format: "%m%d".to_string(),
+        },
+        ProgramRule {
+            filename_pattern: Regex::new(timestamp_pattern).expect("valid regex"),
+            timestamp_format: "%Y-%m-%d_%H-%M-%S".to_string(),
+            time_of_day: None, // catch-all for anything from 13:00 onward
+            title_template: "Afternoon Program - RTSDA | {date}".to_string(),
+            tag: "Afternoon Program".to_string(),
+            season_format: "%Y".to_string(),
+            episode_format: "%m%d".to_string(),
+        },
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn t(hour: u32, min: u32) -> NaiveTime {
+        NaiveTime::from_hms_opt(hour, min, 0).expect("valid time")
+    }
+
+    #[test]
+    fn time_in_window_handles_non_wrapping_range() {
+        assert!(time_in_window(t(10, 0), t(9, 0), t(13, 0)));
+        assert!(!time_in_window(t(13, 0), t(9, 0), t(13, 0))); // end is exclusive
+        assert!(!time_in_window(t(8, 59), t(9, 0), t(13, 0)));
+    }
+
+    #[test]
+    fn time_in_window_handles_midnight_wraparound() {
+        // e.g. an overnight window from 22:00 to 02:00.
+        assert!(time_in_window(t(23, 30), t(22, 0), t(2, 0)));
+        assert!(time_in_window(t(1, 0), t(22, 0), t(2, 0)));
+        assert!(!time_in_window(t(12, 0), t(22, 0), t(2, 0)));
+        assert!(!time_in_window(t(2, 0), t(22, 0), t(2, 0))); // end is exclusive
+    }
+
+    #[test]
+    fn classify_errors_when_no_rule_matches() {
+        let rules = default_rules();
+        let err = classify("not-a-recognized-filename", &rules)
+            .expect_err("no rule should match this filename");
+        assert!(err.to_string().contains("no program rule matched"));
+    }
+
+    #[test]
+    fn classify_falls_through_to_second_rule_by_time_of_day() {
+        let rules = default_rules();
+        let classification = classify("2026-01-04_14-00-00", &rules).expect("should classify");
+        assert_eq!(classification.rule.tag, "Afternoon Program");
+    }
+}